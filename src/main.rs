@@ -1,13 +1,144 @@
-use clap::Parser;
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes128Gcm, Aes256Gcm, Key, KeyInit, Nonce};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use chacha20poly1305::ChaCha20Poly1305;
+use clap::{Parser, ValueEnum};
+use futures::{SinkExt, StreamExt};
+use rand_core::OsRng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::fmt::Debug;
 use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use std::process::Stdio;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::fs::File;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::process::Command;
-use tokio::{io, try_join};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::{io, select, try_join};
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret};
+
+/// Size of each chunk sealed/opened during an encrypted transfer.
+const ENCRYPTED_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Mode tag sent as the first byte of every connection, so a single
+/// `remote_server` can serve the plaintext framed transfer and the
+/// encrypted transfer side by side instead of picking one for its whole
+/// lifetime.
+const TRANSFER_MODE_PLAIN: u8 = 0;
+const TRANSFER_MODE_ENCRYPTED: u8 = 1;
+
+/// Output format for the results printed by [`time`].
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// One human-readable line per step, as before.
+    Text,
+    /// One JSON record per (file, step), including the raw samples.
+    Json,
+}
+
+/// One machine-readable timing result, emitted as a single JSON line when
+/// `--format json` is selected.
+#[derive(Serialize)]
+struct TimeRecord<'a> {
+    file: &'a str,
+    step: &'a str,
+    samples: Vec<f64>,
+    mean: f64,
+    std: f64,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+}
+
+/// AEAD cipher suite to benchmark the encrypted transfer with.
+#[derive(Clone, Copy, ValueEnum)]
+enum Cipher {
+    #[clap(name = "aes-128-gcm")]
+    Aes128Gcm,
+    #[clap(name = "aes-256-gcm")]
+    Aes256Gcm,
+    #[clap(name = "chacha20-poly1305")]
+    Chacha20Poly1305,
+}
+
+impl Cipher {
+    fn id(self) -> u8 {
+        match self {
+            Cipher::Aes128Gcm => 0,
+            Cipher::Aes256Gcm => 1,
+            Cipher::Chacha20Poly1305 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Self {
+        match id {
+            0 => Cipher::Aes128Gcm,
+            1 => Cipher::Aes256Gcm,
+            2 => Cipher::Chacha20Poly1305,
+            _ => panic!("unknown cipher id {}", id),
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Cipher::Aes128Gcm => "aes-128-gcm",
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::Chacha20Poly1305 => "chacha20-poly1305",
+        }
+    }
+}
+
+/// Dispatches seal/open to whichever AEAD cipher was negotiated, so the
+/// key-exchange and chunking code in [`transfer_encrypted`] and
+/// [`decrypt_and_hash`] stays the same across cipher suites.
+enum AeadCipher {
+    Aes128(Aes128Gcm),
+    Aes256(Aes256Gcm),
+    Chacha20(ChaCha20Poly1305),
+}
+
+impl AeadCipher {
+    fn new(cipher: Cipher, shared_secret: &SharedSecret) -> Self {
+        let key: [u8; 32] = Sha256::digest(shared_secret.as_bytes()).into();
+        match cipher {
+            Cipher::Aes128Gcm => {
+                AeadCipher::Aes128(Aes128Gcm::new(Key::<Aes128Gcm>::from_slice(&key[..16])))
+            }
+            Cipher::Aes256Gcm => {
+                AeadCipher::Aes256(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+            }
+            Cipher::Chacha20Poly1305 => AeadCipher::Chacha20(ChaCha20Poly1305::new(
+                chacha20poly1305::Key::from_slice(&key),
+            )),
+        }
+    }
+
+    fn encrypt(&self, nonce: &[u8; 12], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = Nonce::from_slice(nonce);
+        match self {
+            AeadCipher::Aes128(cipher) => cipher.encrypt(nonce, plaintext),
+            AeadCipher::Aes256(cipher) => cipher.encrypt(nonce, plaintext),
+            AeadCipher::Chacha20(cipher) => cipher.encrypt(nonce, plaintext),
+        }
+        .expect("failed to encrypt chunk")
+    }
+
+    fn decrypt(&self, nonce: &[u8; 12], ciphertext: &[u8]) -> Vec<u8> {
+        let nonce = Nonce::from_slice(nonce);
+        match self {
+            AeadCipher::Aes128(cipher) => cipher.decrypt(nonce, ciphertext),
+            AeadCipher::Aes256(cipher) => cipher.decrypt(nonce, ciphertext),
+            AeadCipher::Chacha20(cipher) => cipher.decrypt(nonce, ciphertext),
+        }
+        .expect("failed to decrypt chunk")
+    }
+}
 
 #[derive(Parser)]
 enum Cli {
@@ -22,10 +153,33 @@ enum Cli {
         ip: IpAddr,
         #[clap(long, default_value_t = 5)]
         iterations: i32,
+        /// Also benchmark the transfer over an encrypted channel.
+        #[clap(long)]
+        encrypt: bool,
+        /// Split the file into this many ranges and transfer them over
+        /// concurrent streams, reporting aggregate throughput.
+        #[clap(long, default_value_t = 1)]
+        parallel: usize,
+        /// Cipher suite(s) to use for the encrypted transfer step, each run
+        /// once. Only used when `--encrypt` is set.
+        #[clap(long, value_enum, default_values_t = [Cipher::Aes256Gcm])]
+        cipher: Vec<Cipher>,
+        /// Number of iterations to run and discard before timing starts.
+        #[clap(long, default_value_t = 0)]
+        warmup: usize,
+        /// Output format for the timing results.
+        #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
     RemoteServer {
         #[clap(long, default_value_t = 1144)]
         port: u16,
+        /// Drop a connection if it stalls for longer than this many seconds.
+        #[clap(long, default_value_t = 30)]
+        idle_timeout: u64,
+        /// Maximum number of connections handled concurrently.
+        #[clap(long, default_value_t = 64)]
+        max_connections: usize,
     },
 }
 
@@ -39,11 +193,32 @@ async fn main() {
             port,
             ip,
             iterations,
+            encrypt,
+            parallel,
+            cipher,
+            warmup,
+            format,
         } => {
-            benchmark(files, transcode_seconds, port, ip, iterations).await;
+            benchmark(
+                files,
+                transcode_seconds,
+                port,
+                ip,
+                iterations,
+                encrypt,
+                parallel,
+                cipher,
+                warmup,
+                format,
+            )
+            .await;
         }
-        Cli::RemoteServer { port } => {
-            remote_server(port).await;
+        Cli::RemoteServer {
+            port,
+            idle_timeout,
+            max_connections,
+        } => {
+            remote_server(port, Duration::from_secs(idle_timeout), max_connections).await;
         }
     }
 }
@@ -54,51 +229,163 @@ async fn benchmark(
     port: u16,
     ip: IpAddr,
     iterations: i32,
+    encrypt: bool,
+    parallel: usize,
+    ciphers: Vec<Cipher>,
+    warmup: usize,
+    format: OutputFormat,
 ) {
     let transcode_duration = Duration::from_secs_f64(transcode_seconds);
     let transfer_address = SocketAddr::new(ip, port);
 
     for file_path in file_paths {
-        println!("Benchmark with {}", file_path);
-
-        time("Read file", iterations, || read(&file_path)).await;
+        match format {
+            OutputFormat::Text => println!("Benchmark with {}", file_path),
+            OutputFormat::Json => {}
+        }
 
-        time("Transcoded file", iterations, || {
-            transcode(&file_path, transcode_duration)
+        time(&file_path, "Read file", warmup, iterations, format, || {
+            read(&file_path)
         })
         .await;
 
-        time("Transferred data in LAN", iterations, || {
-            transfer(&file_path, transfer_address)
-        })
+        time(
+            &file_path,
+            "Transcoded file",
+            warmup,
+            iterations,
+            format,
+            || transcode(&file_path, transcode_duration),
+        )
+        .await;
+
+        time(
+            &file_path,
+            "Transferred data in LAN",
+            warmup,
+            iterations,
+            format,
+            || transfer(&file_path, transfer_address),
+        )
         .await;
+
+        if encrypt {
+            for cipher in &ciphers {
+                time(
+                    &file_path,
+                    &format!("Transferred data in LAN (encrypted, {})", cipher.label()),
+                    warmup,
+                    iterations,
+                    format,
+                    || transfer_encrypted(&file_path, transfer_address, *cipher),
+                )
+                .await;
+            }
+        }
+
+        if parallel > 1 {
+            time(
+                &file_path,
+                "Transferred data in LAN (parallel)",
+                warmup,
+                iterations,
+                format,
+                || transfer_parallel(&file_path, transfer_address, parallel),
+            )
+            .await;
+        }
     }
 }
 
-async fn remote_server(port: u16) {
+async fn remote_server(port: u16, idle_timeout: Duration, max_connections: usize) {
     let server = TcpListener::bind(("0.0.0.0", port))
         .await
         .expect("failed to bind");
     println!("Listening on {}", port);
 
+    let semaphore = Arc::new(Semaphore::new(max_connections));
+    let mut handlers = JoinSet::new();
+    let mut shutdown = Box::pin(tokio::signal::ctrl_c());
+
     loop {
-        let (connection, address) = server.accept().await.expect("failed to accept connection");
-        println!("Got connection from {}", address);
-
-        tokio::spawn(async move {
-            let (reader, mut writer) = connection.into_split();
-            let hash = hash(reader).await;
-            writer.write_u8(hash).await.expect("failed to write hash");
-            println!("Finished connection from {}", address);
-        });
+        select! {
+            result = &mut shutdown => {
+                result.expect("failed to listen for ctrl-c");
+                println!("Shutting down, waiting for in-flight connections to finish...");
+                break;
+            }
+            // Drain handlers as they finish so the set doesn't grow without
+            // bound over the server's lifetime.
+            Some(result) = handlers.join_next(), if !handlers.is_empty() => {
+                result.expect("connection handler panicked");
+            }
+            accepted = server.accept() => {
+                let (mut connection, address) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(error) => {
+                        println!("Failed to accept connection: {}", error);
+                        continue;
+                    }
+                };
+                println!("Got connection from {}", address);
+
+                let semaphore = semaphore.clone();
+                handlers.spawn(async move {
+                    let Ok(_permit) = semaphore.acquire().await else {
+                        return;
+                    };
+
+                    let completed = within_idle_timeout(Some(idle_timeout), connection.read_u8())
+                        .await
+                        .map(|mode| mode.expect("failed to read mode tag"));
+                    let completed = match completed {
+                        None => None,
+                        Some(TRANSFER_MODE_PLAIN) => {
+                            let (reader, writer) = connection.into_split();
+                            handle_framed_transfer(reader, writer, idle_timeout).await
+                        }
+                        Some(TRANSFER_MODE_ENCRYPTED) => {
+                            let cipher =
+                                encrypted_handshake(&mut connection, None, Some(idle_timeout))
+                                    .await;
+                            match cipher {
+                                None => None,
+                                Some(cipher) => {
+                                    let (reader, mut writer) = connection.into_split();
+                                    match decrypt_and_hash(&cipher, reader, idle_timeout).await {
+                                        None => None,
+                                        Some(digest) => {
+                                            writer
+                                                .write_all(&digest)
+                                                .await
+                                                .expect("failed to write digest");
+                                            Some(())
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        Some(other) => panic!("unknown transfer mode tag {}", other),
+                    };
+
+                    match completed {
+                        Some(()) => println!("Finished connection from {}", address),
+                        None => println!("Connection from {} idle for too long, dropping it", address),
+                    }
+                });
+            }
+        }
     }
+
+    while handlers.join_next().await.is_some() {}
+    println!("All connections finished");
 }
 
-async fn read(file_path: &str) -> u8 {
+async fn read(file_path: &str) -> [u8; 32] {
     hash(File::open(file_path).await.expect("failed to open file")).await
 }
 
-async fn transcode(file_path: &str, duration: Duration) -> u8 {
+async fn transcode(file_path: &str, duration: Duration) -> [u8; 32] {
     let mut child = Command::new("ffmpeg")
         .args([
             "-hide_banner",
@@ -151,52 +438,480 @@ async fn transcode(file_path: &str, duration: Duration) -> u8 {
     hash
 }
 
-async fn transfer(file_path: &str, address: SocketAddr) -> u8 {
-    let connection = TcpStream::connect(address)
+async fn transfer(file_path: &str, address: SocketAddr) -> [u8; 32] {
+    let length = tokio::fs::metadata(file_path)
+        .await
+        .expect("failed to stat file")
+        .len();
+    transfer_range(file_path, address, 0, length).await
+}
+
+/// Size of each data frame sent over the framed transfer protocol.
+const TRANSFER_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The first frame of a framed transfer: which file, which byte range, and
+/// the digest the sender computed for it, so the receiver knows exactly how
+/// many bytes to expect. The receiver checks the byte count only; it does
+/// not compare this digest, but reports its own back to the sender.
+struct TransferMetadata {
+    file_name: String,
+    start: u64,
+    length: u64,
+    digest: [u8; 32],
+}
+
+impl TransferMetadata {
+    fn encode(&self) -> Bytes {
+        let name_bytes = self.file_name.as_bytes();
+        let mut buffer = BytesMut::with_capacity(4 + name_bytes.len() + 8 + 8 + 32);
+        buffer.put_u32(name_bytes.len() as u32);
+        buffer.put_slice(name_bytes);
+        buffer.put_u64(self.start);
+        buffer.put_u64(self.length);
+        buffer.put_slice(&self.digest);
+        buffer.freeze()
+    }
+
+    fn decode(mut frame: BytesMut) -> Self {
+        let name_len = frame.get_u32() as usize;
+        let file_name = String::from_utf8(frame.split_to(name_len).to_vec())
+            .expect("invalid metadata frame: file name is not valid UTF-8");
+        let start = frame.get_u64();
+        let length = frame.get_u64();
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&frame[..32]);
+        TransferMetadata {
+            file_name,
+            start,
+            length,
+            digest,
+        }
+    }
+}
+
+/// Transfers the byte range `start..start+length` of `file_path` to the
+/// remote server as a length-delimited metadata frame followed by data
+/// frames, then verifies the digest the server reports back against the
+/// locally computed digest of the same range.
+async fn transfer_range(file_path: &str, address: SocketAddr, start: u64, length: u64) -> [u8; 32] {
+    let mut connection = TcpStream::connect(address)
         .await
         .expect("failed to connect");
+    connection
+        .write_u8(TRANSFER_MODE_PLAIN)
+        .await
+        .expect("failed to send mode tag");
+    let (reader, writer) = connection.into_split();
+    let mut framed_reader = FramedRead::new(reader, LengthDelimitedCodec::new());
+    let mut framed_writer = FramedWrite::new(writer, LengthDelimitedCodec::new());
 
-    let (mut reader, mut writer) = connection.into_split();
+    let digest = hash_range(file_path, start, length).await;
+    let file_name = Path::new(file_path)
+        .file_name()
+        .expect("file path has no file name")
+        .to_string_lossy()
+        .into_owned();
+    let metadata = TransferMetadata {
+        file_name,
+        start,
+        length,
+        digest,
+    };
+    framed_writer
+        .send(metadata.encode())
+        .await
+        .expect("failed to send metadata frame");
 
     let mut file = File::open(file_path).await.expect("failed to open file");
-    io::copy(&mut file, &mut writer)
+    file.seek(io::SeekFrom::Start(start))
         .await
-        .expect("failed to send file");
+        .expect("failed to seek file");
+    let mut remaining = length;
+    let mut buffer = vec![0u8; TRANSFER_CHUNK_SIZE];
+    while remaining > 0 {
+        let to_read = remaining.min(buffer.len() as u64) as usize;
+        let bytes = file
+            .read(&mut buffer[..to_read])
+            .await
+            .expect("failed to read file");
+        if bytes == 0 {
+            break;
+        }
+        framed_writer
+            .send(Bytes::copy_from_slice(&buffer[..bytes]))
+            .await
+            .expect("failed to send data frame");
+        remaining -= bytes as u64;
+    }
+    framed_writer
+        .close()
+        .await
+        .expect("failed to close connection");
+
+    let response = framed_reader
+        .next()
+        .await
+        .expect("connection closed before receiving a response")
+        .expect("failed to read response frame");
+    decode_transfer_response(response, digest)
+}
+
+fn decode_transfer_response(mut frame: BytesMut, expected_digest: [u8; 32]) -> [u8; 32] {
+    let status = frame.get_u8();
+    if status != 0 {
+        panic!(
+            "remote server reported a transfer error: {}",
+            String::from_utf8_lossy(&frame)
+        );
+    }
+
+    let mut remote_digest = [0u8; 32];
+    remote_digest.copy_from_slice(&frame[..32]);
+    if remote_digest != expected_digest {
+        panic!(
+            "digest mismatch: server computed {:x?}, expected {:x?}",
+            remote_digest, expected_digest
+        );
+    }
+
+    remote_digest
+}
+
+/// Reads one framed transfer from `reader` (a metadata frame followed by
+/// data frames), rejects short/over-long transfers, and writes a response
+/// frame to `writer`: a digest on success, or an error message. Each frame
+/// read is individually bounded by `idle_timeout`, so the clock resets as
+/// long as data keeps arriving, no matter how long the transfer as a whole
+/// takes. Returns `None` if a read stalls past `idle_timeout`.
+async fn handle_framed_transfer(
+    reader: impl AsyncRead + Unpin,
+    writer: impl AsyncWrite + Unpin,
+    idle_timeout: Duration,
+) -> Option<()> {
+    let mut framed_reader = FramedRead::new(reader, LengthDelimitedCodec::new());
+    let mut framed_writer = FramedWrite::new(writer, LengthDelimitedCodec::new());
+
+    let metadata_frame = within_idle_timeout(Some(idle_timeout), framed_reader.next())
+        .await?
+        .expect("connection closed before metadata frame")
+        .expect("failed to read metadata frame");
+    let metadata = TransferMetadata::decode(metadata_frame);
+    println!(
+        "Receiving \"{}\" range {}..{} ({} bytes)",
+        metadata.file_name,
+        metadata.start,
+        metadata.start + metadata.length,
+        metadata.length
+    );
+
+    let mut digest = Sha256::new();
+    let mut received = 0u64;
+    while let Some(frame) = within_idle_timeout(Some(idle_timeout), framed_reader.next()).await? {
+        let chunk = frame.expect("failed to read data frame");
+        received += chunk.len() as u64;
+        digest.update(&chunk);
+    }
+
+    let response = if received != metadata.length {
+        let message = format!(
+            "expected {} bytes but received {}",
+            metadata.length, received
+        );
+        println!("Rejecting transfer: {}", message);
+        let mut frame = BytesMut::with_capacity(1 + message.len());
+        frame.put_u8(1);
+        frame.put_slice(message.as_bytes());
+        frame.freeze()
+    } else {
+        let mut frame = BytesMut::with_capacity(33);
+        frame.put_u8(0);
+        frame.put_slice(&digest.finalize());
+        frame.freeze()
+    };
+    framed_writer
+        .send(response)
+        .await
+        .expect("failed to send response frame");
+    Some(())
+}
+
+/// Hashes the byte range `start..start+length` of `file_path`.
+async fn hash_range(file_path: &str, start: u64, length: u64) -> [u8; 32] {
+    let mut file = File::open(file_path).await.expect("failed to open file");
+    file.seek(io::SeekFrom::Start(start))
+        .await
+        .expect("failed to seek file");
+    hash(file.take(length)).await
+}
+
+/// Splits `file_path` into `parallel` contiguous ranges and transfers them
+/// concurrently over separate `TcpStream`s, returning the aggregate
+/// throughput in MB/s computed from the slowest stream's elapsed time.
+async fn transfer_parallel(file_path: &str, address: SocketAddr, parallel: usize) -> f64 {
+    let total_length = tokio::fs::metadata(file_path)
+        .await
+        .expect("failed to stat file")
+        .len();
+    let chunk_length = total_length.div_ceil(parallel as u64).max(1);
+
+    let mut tasks = JoinSet::new();
+    let mut start = 0;
+    while start < total_length {
+        let length = chunk_length.min(total_length - start);
+        let file_path = file_path.to_string();
+        tasks.spawn(async move {
+            let started = Instant::now();
+            transfer_range(&file_path, address, start, length).await;
+            (length, started.elapsed())
+        });
+        start += length;
+    }
+
+    let mut total_bytes = 0u64;
+    let mut max_elapsed = Duration::ZERO;
+    while let Some(result) = tasks.join_next().await {
+        let (length, elapsed) = result.expect("parallel transfer task panicked");
+        total_bytes += length;
+        max_elapsed = max_elapsed.max(elapsed);
+    }
+
+    total_bytes as f64 / 1_000_000.0 / max_elapsed.as_secs_f64()
+}
+
+/// Awaits `fut`, resetting the clock on every call. When `idle_timeout` is
+/// set and the wait stalls past it, returns `None` instead of panicking so
+/// the caller can drop the connection gracefully; `None` (used by the
+/// client, which has nothing to time out against) always resolves to
+/// `Some`.
+async fn within_idle_timeout<F, O>(idle_timeout: Option<Duration>, fut: F) -> Option<O>
+where
+    F: Future<Output = O>,
+{
+    match idle_timeout {
+        Some(idle_timeout) => tokio::time::timeout(idle_timeout, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+/// Runs the X25519 key exchange over `stream` and derives the AEAD cipher
+/// from the resulting shared secret. The initiating side passes the cipher
+/// it wants to use in `cipher` and sends its id first; the accepting side
+/// passes `None` and reads that id off the wire instead. Both sides then
+/// exchange ephemeral public keys the same way: each sends its own before
+/// reading the peer's.
+///
+/// `idle_timeout` bounds each individual read (reset on every read, not the
+/// handshake as a whole); pass `None` from the client, which has no idle
+/// timeout of its own.
+async fn encrypted_handshake(
+    stream: &mut TcpStream,
+    cipher: Option<Cipher>,
+    idle_timeout: Option<Duration>,
+) -> Option<AeadCipher> {
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+
+    let (mut reader, mut writer) = stream.split();
+
+    let cipher = match cipher {
+        Some(cipher) => {
+            writer
+                .write_u8(cipher.id())
+                .await
+                .expect("failed to send cipher id");
+            cipher
+        }
+        None => {
+            let id = within_idle_timeout(idle_timeout, reader.read_u8())
+                .await?
+                .expect("failed to read cipher id");
+            Cipher::from_id(id)
+        }
+    };
+
+    writer
+        .write_all(public.as_bytes())
+        .await
+        .expect("failed to send public key");
+    let mut peer_bytes = [0u8; 32];
+    within_idle_timeout(idle_timeout, reader.read_exact(&mut peer_bytes))
+        .await?
+        .expect("failed to read public key");
+
+    let shared_secret = secret.diffie_hellman(&PublicKey::from(peer_bytes));
+    Some(AeadCipher::new(cipher, &shared_secret))
+}
+
+/// Turns a monotonic counter into a 12-byte AEAD nonce.
+fn next_nonce(counter: &mut u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(&counter.to_be_bytes());
+    *counter += 1;
+    nonce
+}
+
+async fn transfer_encrypted(file_path: &str, address: SocketAddr, cipher: Cipher) -> [u8; 32] {
+    let mut connection = TcpStream::connect(address)
+        .await
+        .expect("failed to connect");
+    connection
+        .write_u8(TRANSFER_MODE_ENCRYPTED)
+        .await
+        .expect("failed to send mode tag");
+    let cipher = encrypted_handshake(&mut connection, Some(cipher), None)
+        .await
+        .expect("client handshake has no idle timeout to stall against");
+
+    let (mut reader, mut writer) = connection.into_split();
+
+    let mut file = File::open(file_path).await.expect("failed to open file");
+    let mut buffer = vec![0u8; ENCRYPTED_CHUNK_SIZE];
+    let mut counter = 0u64;
+    loop {
+        let bytes = file.read(&mut buffer).await.expect("failed to read file");
+        if bytes == 0 {
+            break;
+        }
+
+        let nonce = next_nonce(&mut counter);
+        let ciphertext = cipher.encrypt(&nonce, &buffer[..bytes]);
+        writer
+            .write_all(&nonce)
+            .await
+            .expect("failed to send chunk nonce");
+        writer
+            .write_u32(ciphertext.len() as u32)
+            .await
+            .expect("failed to send chunk length");
+        writer
+            .write_all(&ciphertext)
+            .await
+            .expect("failed to send chunk");
+    }
     drop(writer);
 
-    reader.read_u8().await.expect("failed to read file")
+    let mut remote_digest = [0u8; 32];
+    reader
+        .read_exact(&mut remote_digest)
+        .await
+        .expect("failed to read digest");
+
+    let local_digest = hash(File::open(file_path).await.expect("failed to open file")).await;
+    if remote_digest != local_digest {
+        panic!(
+            "digest mismatch after encrypted transfer: server computed {:x?}, expected {:x?}",
+            remote_digest, local_digest
+        );
+    }
+
+    local_digest
+}
+
+/// Reads nonce-prefixed, length-prefixed AEAD chunks from `reader` until it
+/// closes, decrypting and hashing each one in turn. Each read is
+/// individually bounded by `idle_timeout`, so the clock resets on every
+/// chunk rather than timing out the transfer as a whole. Returns `None` if
+/// a read stalls past `idle_timeout`.
+async fn decrypt_and_hash(
+    cipher: &AeadCipher,
+    mut reader: impl AsyncRead + Unpin,
+    idle_timeout: Duration,
+) -> Option<[u8; 32]> {
+    let mut digest = Sha256::new();
+    loop {
+        let mut nonce = [0u8; 12];
+        match within_idle_timeout(Some(idle_timeout), reader.read_exact(&mut nonce)).await? {
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => panic!("failed to read chunk nonce: {}", error),
+        }
+
+        let length = within_idle_timeout(Some(idle_timeout), reader.read_u32())
+            .await?
+            .expect("failed to read chunk length") as usize;
+        let mut ciphertext = vec![0u8; length];
+        within_idle_timeout(Some(idle_timeout), reader.read_exact(&mut ciphertext))
+            .await?
+            .expect("failed to read chunk");
+        let plaintext = cipher.decrypt(&nonce, &ciphertext);
+
+        digest.update(&plaintext);
+    }
+
+    Some(digest.finalize().into())
 }
 
-async fn hash(mut reader: impl AsyncRead + Unpin) -> u8 {
+async fn hash(mut reader: impl AsyncRead + Unpin) -> [u8; 32] {
+    let mut digest = Sha256::new();
     let mut buffer = [0; 1024];
-    let mut hash = 0;
     loop {
         let bytes = reader.read(&mut buffer).await.expect("failed to read file");
         if bytes == 0 {
             break;
         }
-        for &byte in buffer[..bytes].iter() {
-            hash ^= byte;
-        }
+        digest.update(&buffer[..bytes]);
     }
 
-    hash
+    digest.finalize().into()
 }
 
-async fn time<C, F, O>(name: &str, iterations: i32, f: C)
-where
+/// Nearest-rank percentile of `sorted_samples`, which must already be sorted
+/// in ascending order.
+fn percentile(sorted_samples: &[f64], p: f64) -> f64 {
+    let rank = ((p / 100.0) * sorted_samples.len() as f64).ceil() as usize;
+    let index = rank.saturating_sub(1).min(sorted_samples.len() - 1);
+    sorted_samples[index]
+}
+
+async fn time<C, F, O>(
+    file_path: &str,
+    step: &str,
+    warmup: usize,
+    iterations: i32,
+    format: OutputFormat,
+    f: C,
+) where
     C: Fn() -> F,
     F: Future<Output = O>,
     O: Debug,
 {
-    let mut samples = vec![];
     let mut output = None;
+    for _ in 0..warmup {
+        output = Some(f().await);
+    }
+
+    let mut samples = vec![];
     for _ in 0..iterations {
         let start = Instant::now();
         output = Some(f().await);
         samples.push(start.elapsed().as_secs_f64());
     }
 
+    if samples.is_empty() {
+        match format {
+            OutputFormat::Text => {
+                println!("{} skipped (0 iterations, got {:?})", step, output);
+            }
+            OutputFormat::Json => {
+                let record = TimeRecord {
+                    file: file_path,
+                    step,
+                    samples,
+                    mean: 0.0,
+                    std: 0.0,
+                    p50: 0.0,
+                    p90: 0.0,
+                    p99: 0.0,
+                };
+                println!(
+                    "{}",
+                    serde_json::to_string(&record).expect("failed to serialize timing record")
+                );
+            }
+        }
+        return;
+    }
+
     let n = samples.len() as f64;
     let avg = samples.iter().sum::<f64>() / n;
     let variance = samples
@@ -206,11 +921,40 @@ where
         / (n - 1.0);
     let std = variance.sqrt();
 
-    println!(
-        "{} in {:.1} ± {:.1} s (got {:?})",
-        name,
-        avg,
-        std,
-        output.expect("at least one iteration")
-    );
+    let mut sorted_samples = samples.clone();
+    sorted_samples.sort_by(|a, b| a.total_cmp(b));
+    let p50 = percentile(&sorted_samples, 50.0);
+    let p90 = percentile(&sorted_samples, 90.0);
+    let p99 = percentile(&sorted_samples, 99.0);
+
+    match format {
+        OutputFormat::Text => {
+            println!(
+                "{} in {:.1} ± {:.1} s (p50 {:.1}, p90 {:.1}, p99 {:.1}) (got {:?})",
+                step,
+                avg,
+                std,
+                p50,
+                p90,
+                p99,
+                output.expect("at least one iteration")
+            );
+        }
+        OutputFormat::Json => {
+            let record = TimeRecord {
+                file: file_path,
+                step,
+                samples,
+                mean: avg,
+                std,
+                p50,
+                p90,
+                p99,
+            };
+            println!(
+                "{}",
+                serde_json::to_string(&record).expect("failed to serialize timing record")
+            );
+        }
+    }
 }